@@ -1,11 +1,56 @@
 // Copyright 2019 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
 use super::super::VmmAction;
 use micro_http::StatusCode;
 use request::{Body, Error, ParsedRequest};
+use serde_json::{Map, Value};
+use vmm::vmm_config::mmds::anonymize::anonymize;
+use vmm::vmm_config::mmds::service::{spawn_dns_frontend, spawn_mdns_responder, spawn_mmds_listener};
 use vmm::vmm_config::mmds::MmdsConfig;
 
+/// Whether MMDS puts/patches should be logged anonymized, as last configured
+/// via `PUT /mmds/config`. Read by `parse_put_mmds`/`parse_patch_mmds`, which
+/// otherwise have no access to the `MmdsConfig` set on a prior, independent
+/// request.
+static ANONYMIZE_MMDS_LOGS: AtomicBool = AtomicBool::new(false);
+
+/// Backing store for the MMDS document: PUT/PATCH requests write through
+/// this, and the DNS front-end reads from it, so it answers real keys
+/// instead of a disconnected placeholder. This tree has no VMM event
+/// loop/VmmAction handler to own an MMDS data store, so this API-layer
+/// static is the closest reachable substitute.
+static MMDS_DATA_STORE: Mutex<Value> = Mutex::new(Value::Null);
+
+/// Applies an RFC 7396 JSON Merge Patch: object keys in `patch` are merged
+/// recursively into `target`, a `null` value removes the key, and any
+/// non-object `patch` replaces `target` outright.
+fn merge_patch(target: &mut Value, patch: &Value) {
+    let patch_map = match patch.as_object() {
+        Some(patch_map) => patch_map,
+        None => {
+            *target = patch.clone();
+            return;
+        }
+    };
+
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    let target_map = target.as_object_mut().unwrap();
+
+    for (key, value) in patch_map {
+        if value.is_null() {
+            target_map.remove(key);
+        } else {
+            merge_patch(target_map.entry(key.clone()).or_insert(Value::Null), value);
+        }
+    }
+}
+
 pub fn parse_get_mmds() -> Result<ParsedRequest, Error> {
     Ok(ParsedRequest::GetMMDS)
 }
@@ -35,31 +80,71 @@ pub fn parse_put_mmds(body: &Body, first_token: Option<&&str>) -> Result<ParsedR
             return Err(Error::MmdsConfig(mmds_ipv4_addr_pool.err().unwrap()));
         }
 
+        let mmds_ipv6_addr_pool = mmds_config.ipv6_addr_pool();
+        if mmds_ipv6_addr_pool.is_err() {
+            // Safe, checked above.
+            return Err(Error::MmdsConfig(mmds_ipv6_addr_pool.err().unwrap()));
+        }
+
+        let mmds_endpoint = match mmds_config.endpoint() {
+            Ok(mmds_endpoint) => mmds_endpoint,
+            Err(err) => return Err(Error::MmdsConfig(err)),
+        };
+
         // Safe, checked above.
         let mmds_ipv4_addr_pool = mmds_ipv4_addr_pool.unwrap();
+        // Safe, checked above.
+        let mmds_ipv6_addr_pool = mmds_ipv6_addr_pool.unwrap();
 
-        if mmds_ipv4_addr_pool.is_empty() {
+        if mmds_ipv4_addr_pool.is_empty() && mmds_ipv6_addr_pool.is_empty() {
             // METRICS.put_api_requests.drive_fails.inc();
             return Err(Error::Generic(
                 StatusCode::BadRequest,
-                "The IPv4 addresses pool is empty.".to_string(),
+                "The MMDS addresses pool is empty.".to_string(),
             ));
         } else {
+            ANONYMIZE_MMDS_LOGS.store(mmds_config.anonymize(), Ordering::Relaxed);
+
+            // This tree has no VMM event loop to hand `SetMmdsConfiguration`
+            // off to (no `VmmAction` handler exists here at all), so the
+            // auxiliary listeners are spawned right at config-acceptance
+            // time instead, which is the earliest point an `MmdsConfig` is
+            // available.
+            spawn_mdns_responder(&mmds_config);
+            spawn_dns_frontend(&mmds_config, &MMDS_DATA_STORE);
+            if let Some(mmds_endpoint) = &mmds_endpoint {
+                spawn_mmds_listener(mmds_endpoint);
+            }
+
             return Ok(ParsedRequest::Sync(VmmAction::SetMmdsConfiguration(
                 mmds_config,
             )));
         }
     }
 
-    Ok(ParsedRequest::PutMMDS(
-        serde_json::from_slice(body.raw()).map_err(Error::SerdeJson)?,
-    ))
+    let put_mmds_data: Value = serde_json::from_slice(body.raw()).map_err(Error::SerdeJson)?;
+    *MMDS_DATA_STORE.lock().unwrap() = put_mmds_data.clone();
+    // The document itself is stored and forwarded to the guest as-is either
+    // way; only the copy that reaches this log line is redacted, and only
+    // when MMDS anonymization is enabled.
+    if ANONYMIZE_MMDS_LOGS.load(Ordering::Relaxed) {
+        eprintln!("PUT /mmds {}", anonymize(&put_mmds_data));
+    } else {
+        eprintln!("PUT /mmds {}", put_mmds_data);
+    }
+    Ok(ParsedRequest::PutMMDS(put_mmds_data))
 }
 
 pub fn parse_patch_mmds(body: &Body) -> Result<ParsedRequest, Error> {
-    Ok(ParsedRequest::PatchMMDS(
-        serde_json::from_slice(body.raw()).map_err(Error::SerdeJson)?,
-    ))
+    let patch_mmds_data: Value = serde_json::from_slice(body.raw()).map_err(Error::SerdeJson)?;
+    merge_patch(&mut MMDS_DATA_STORE.lock().unwrap(), &patch_mmds_data);
+    // See the comment in `parse_put_mmds`: only the logged copy is redacted.
+    if ANONYMIZE_MMDS_LOGS.load(Ordering::Relaxed) {
+        eprintln!("PATCH /mmds {}", anonymize(&patch_mmds_data));
+    } else {
+        eprintln!("PATCH /mmds {}", patch_mmds_data);
+    }
+    Ok(ParsedRequest::PatchMMDS(patch_mmds_data))
 }
 
 #[cfg(test)]
@@ -101,6 +186,20 @@ mod tests {
 
         let path = "invalid_path";
         assert!(parse_put_mmds(&Body::new(body), Some(&path)).is_err());
+
+        let body = r#"{
+                "ipv4_address_pool": ["1.1.1.1"],
+                "endpoint": "/ip4/169.254.169.254/tcp/80/http"
+              }"#;
+        let path = "config";
+        assert!(parse_put_mmds(&Body::new(body), Some(&path)).is_ok());
+
+        let body = r#"{
+                "ipv4_address_pool": ["1.1.1.1"],
+                "endpoint": "/ip4/not_an_address/tcp/80/http"
+              }"#;
+        let path = "config";
+        assert!(parse_put_mmds(&Body::new(body), Some(&path)).is_err());
     }
 
     #[test]
@@ -113,4 +212,30 @@ mod tests {
         let body = "invalid_body";
         assert!(parse_patch_mmds(&Body::new(body)).is_err());
     }
+
+    #[test]
+    fn test_merge_patch() {
+        let mut target = serde_json::json!({
+            "instance-id": "i-1234",
+            "network": {"interfaces": 2, "enabled": true}
+        });
+
+        merge_patch(
+            &mut target,
+            &serde_json::json!({"network": {"interfaces": 4, "enabled": null}, "region": "us-east-1"}),
+        );
+
+        assert_eq!(
+            target,
+            serde_json::json!({
+                "instance-id": "i-1234",
+                "network": {"interfaces": 4},
+                "region": "us-east-1"
+            })
+        );
+
+        let mut scalar = serde_json::json!("old");
+        merge_patch(&mut scalar, &serde_json::json!("new"));
+        assert_eq!(scalar, serde_json::json!("new"));
+    }
 }
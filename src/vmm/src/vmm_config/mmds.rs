@@ -1,31 +1,190 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+pub mod anonymize;
+pub mod dns;
+pub mod endpoint;
+pub mod mdns;
+pub mod service;
+
 use std::fmt::{Display, Formatter};
 
-use std::net::{AddrParseError, Ipv4Addr};
+use std::net::{AddrParseError, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
 use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use self::endpoint::MmdsEndpoint;
 
 /// This struct represents the configuration realted to the MMDS service.
 #[derive(Debug, Deserialize, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct MmdsConfig {
     /// Pool of MMDS endpoints..
+    #[serde(default)]
     ipv4_address_pool: Vec<String>,
+    /// Pool of MMDS IPv6 endpoints.
+    #[serde(default)]
+    ipv6_address_pool: Vec<String>,
+    /// Hostname guests can resolve, via mDNS, to an address from the pool.
+    #[serde(default)]
+    mdns_hostname: Option<String>,
+    /// Multiaddr-style endpoint string (e.g. `/ip4/169.254.169.254/tcp/80/http`)
+    /// overriding the address, port and protocol the MMDS service listens on.
+    #[serde(default)]
+    endpoint: Option<String>,
+    /// Whether the DNS TXT front-end for retrieving metadata keys is enabled.
+    #[serde(default)]
+    dns_enabled: bool,
+    /// Whether MMDS puts/patches should be anonymized before being logged.
+    #[serde(default)]
+    anonymize: bool,
 }
 
 impl MmdsConfig {
-    /// Parse Vec<String> to Vec<Ipv4Addr>.
-    /// Error out if there parse error encountered.
+    /// Parse Vec<String> to Vec<Ipv4Addr>, resolving any entry that isn't a
+    /// literal IPv4 address via DNS and expanding it into every address it
+    /// resolves to.
+    /// Error out if an entry that isn't a literal IPv4 address fails to
+    /// resolve to at least one IPv4 address.
     pub fn ipv4_addr_pool(&self) -> Result<Vec<Ipv4Addr>, Error> {
         let mut ipv4_addr_vec = Vec::new();
         for s in self.ipv4_address_pool.iter() {
-	    let ipv4_addr = Ipv4Addr::from_str(s).map_err(Error::IPv4ParseError)?;
-            ipv4_addr_vec.push(ipv4_addr);
+	    match Ipv4Addr::from_str(s) {
+		Ok(ipv4_addr) => ipv4_addr_vec.push(ipv4_addr),
+		Err(_) => ipv4_addr_vec.extend(resolve_ipv4_hostname(s)?),
+	    }
 	}
 
         Ok(ipv4_addr_vec)
     }
+
+    /// Parse Vec<String> to Vec<Ipv6Addr>.
+    /// Error out if there parse error encountered.
+    pub fn ipv6_addr_pool(&self) -> Result<Vec<Ipv6Addr>, Error> {
+        let mut ipv6_addr_vec = Vec::new();
+        for s in self.ipv6_address_pool.iter() {
+	    let ipv6_addr = Ipv6Addr::from_str(s).map_err(Error::Ipv6ParseError)?;
+            ipv6_addr_vec.push(ipv6_addr);
+	}
+
+        Ok(ipv6_addr_vec)
+    }
+
+    /// Resolves both address pools once and returns them bundled as a
+    /// `ResolvedMmdsPools`, for the network interception layer to match
+    /// guest-bound packets against via `ResolvedMmdsPools::matches_ipv4`/
+    /// `matches_ipv6`.
+    ///
+    /// Callers should resolve once per config change (e.g. when handling a
+    /// `PUT /mmds/config`) and hold on to the result, rather than calling
+    /// this per packet: a pool entry may be a hostname (see
+    /// `ipv4_addr_pool`), and re-resolving it on every match would block the
+    /// caller for up to `DNS_RESOLUTION_TIMEOUT` per check.
+    pub fn resolve_pools(&self) -> Result<ResolvedMmdsPools, Error> {
+        Ok(ResolvedMmdsPools {
+            ipv4_addr_pool: self.ipv4_addr_pool()?,
+            ipv6_addr_pool: self.ipv6_addr_pool()?,
+        })
+    }
+
+    /// Returns the configured mDNS hostname, if any.
+    pub fn mdns_hostname(&self) -> Option<&str> {
+        self.mdns_hostname.as_ref().map(String::as_str)
+    }
+
+    /// Parses the configured multiaddr-style endpoint string, if any.
+    pub fn endpoint(&self) -> Result<Option<MmdsEndpoint>, Error> {
+        match &self.endpoint {
+            Some(endpoint) => Ok(Some(
+                self::endpoint::parse_endpoint(endpoint).map_err(Error::EndpointParseError)?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Whether the DNS TXT front-end is enabled.
+    pub fn dns_enabled(&self) -> bool {
+        self.dns_enabled
+    }
+
+    /// Whether MMDS puts/patches should be anonymized before being logged.
+    pub fn anonymize(&self) -> bool {
+        self.anonymize
+    }
+}
+
+/// An `MmdsConfig`'s address pools, resolved once via `MmdsConfig::resolve_pools`
+/// and cached by the caller for repeated matching against guest-bound packets.
+pub struct ResolvedMmdsPools {
+    ipv4_addr_pool: Vec<Ipv4Addr>,
+    ipv6_addr_pool: Vec<Ipv6Addr>,
+}
+
+impl ResolvedMmdsPools {
+    /// Returns whether `address` is one of the resolved IPv4 pool addresses.
+    pub fn matches_ipv4(&self, address: Ipv4Addr) -> bool {
+        self.ipv4_addr_pool.contains(&address)
+    }
+
+    /// Returns whether `address` is one of the resolved IPv6 pool addresses.
+    pub fn matches_ipv6(&self, address: Ipv6Addr) -> bool {
+        self.ipv6_addr_pool.contains(&address)
+    }
+}
+
+/// Upper bound on how long a single pool-entry DNS resolution may take.
+/// Without this, a hostname backed by a slow or unresponsive resolver would
+/// hang the API request-handling thread indefinitely.
+const DNS_RESOLUTION_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Resolves `hostname` to its IPv4 addresses via the system resolver
+/// (`getaddrinfo`, through `std`'s `ToSocketAddrs`), bounded by
+/// `DNS_RESOLUTION_TIMEOUT`.
+fn resolve_ipv4_hostname(hostname: &str) -> Result<Vec<Ipv4Addr>, Error> {
+    resolve_ipv4_hostname_with(hostname, system_resolve)
+}
+
+/// Looks up `hostname` via the system resolver, returning every address it
+/// resolves to.
+fn system_resolve(hostname: &str) -> std::io::Result<Vec<SocketAddr>> {
+    Ok((hostname, 0).to_socket_addrs()?.collect())
+}
+
+/// Runs `resolve` on a background thread so a hanging resolver can't block
+/// the caller past `DNS_RESOLUTION_TIMEOUT`, then filters the result down to
+/// IPv4 addresses. Factored out of `resolve_ipv4_hostname` so tests can
+/// substitute `resolve` for a fake resolver instead of hitting the network.
+fn resolve_ipv4_hostname_with(
+    hostname: &str,
+    resolve: impl FnOnce(&str) -> std::io::Result<Vec<SocketAddr>> + Send + 'static,
+) -> Result<Vec<Ipv4Addr>, Error> {
+    let (tx, rx) = mpsc::channel();
+    let owned_hostname = hostname.to_string();
+    thread::spawn(move || {
+	// The receiver may already be gone if we timed out; ignore that.
+	let _ = tx.send(resolve(&owned_hostname));
+    });
+
+    let addrs = rx
+	.recv_timeout(DNS_RESOLUTION_TIMEOUT)
+	.map_err(|_| Error::ResolutionFailed(hostname.to_string()))?
+	.map_err(|_| Error::ResolutionFailed(hostname.to_string()))?;
+
+    let ipv4_addrs: Vec<Ipv4Addr> = addrs
+	.into_iter()
+	.filter_map(|addr| match addr {
+	    SocketAddr::V4(v4) => Some(*v4.ip()),
+	    SocketAddr::V6(_) => None,
+	})
+	.collect();
+
+    if ipv4_addrs.is_empty() {
+	return Err(Error::ResolutionFailed(hostname.to_string()));
+    }
+
+    Ok(ipv4_addrs)
 }
 
 #[derive(Debug)]
@@ -35,6 +194,12 @@ pub enum Error {
     SetMmdsConfigurationNotAllowedPostBoot,
     /// IPv4 parse error.
     IPv4ParseError(AddrParseError),
+    /// IPv6 parse error.
+    Ipv6ParseError(AddrParseError),
+    /// A pool entry is neither a literal address nor a name that resolves.
+    ResolutionFailed(String),
+    /// The configured multiaddr-style endpoint string is invalid.
+    EndpointParseError(self::endpoint::Error),
 }
 
 impl Display for Error {
@@ -44,6 +209,11 @@ impl Display for Error {
                 write!(f, "Setting MMDS configuration is not allowed after boot.",)
             }
             Error::IPv4ParseError(err) => write!(f, "{:?}", err),
+            Error::Ipv6ParseError(err) => write!(f, "{:?}", err),
+            Error::ResolutionFailed(name) => {
+                write!(f, "Failed to resolve MMDS address pool entry `{}`.", name)
+            }
+            Error::EndpointParseError(err) => write!(f, "{}", err),
         }
     }
 }
@@ -62,18 +232,163 @@ mod tests {
     #[test]
     fn test_ipv4_addr_pool() {
         let mmds_config = MmdsConfig {
-	    ipv4_address_pool: vec!["1.1.1.1".to_string(), "2.2.2.2".to_string()]
+	    ipv4_address_pool: vec!["1.1.1.1".to_string(), "2.2.2.2".to_string()],
+	    ipv6_address_pool: Vec::new(),
+	    mdns_hostname: None,
+	    endpoint: None,
+	    dns_enabled: false,
+	    anonymize: false,
         };
 	assert!(mmds_config.ipv4_addr_pool().is_ok());
 
         let mmds_config = MmdsConfig {
-	    ipv4_address_pool: Vec::new()
+	    ipv4_address_pool: Vec::new(),
+	    ipv6_address_pool: Vec::new(),
+	    mdns_hostname: None,
+	    endpoint: None,
+	    dns_enabled: false,
+	    anonymize: false,
         };
 	assert!(mmds_config.ipv4_addr_pool().is_ok());
 
         let wrong_mmds_config = MmdsConfig {
-	    ipv4_address_pool: vec!["1.1.1.1.1".to_string()]
+	    ipv4_address_pool: vec!["1.1.1.1.1".to_string()],
+	    ipv6_address_pool: Vec::new(),
+	    mdns_hostname: None,
+	    endpoint: None,
+	    dns_enabled: false,
+	    anonymize: false,
         };
 	assert!(wrong_mmds_config.ipv4_addr_pool().is_err());
     }
+
+    #[test]
+    fn test_ipv6_addr_pool() {
+        let mmds_config = MmdsConfig {
+	    ipv4_address_pool: Vec::new(),
+	    ipv6_address_pool: vec!["fe80::1".to_string(), "fe80::2".to_string()],
+	    mdns_hostname: None,
+	    endpoint: None,
+	    dns_enabled: false,
+	    anonymize: false,
+        };
+	assert!(mmds_config.ipv6_addr_pool().is_ok());
+
+        let mmds_config = MmdsConfig {
+	    ipv4_address_pool: Vec::new(),
+	    ipv6_address_pool: Vec::new(),
+	    mdns_hostname: None,
+	    endpoint: None,
+	    dns_enabled: false,
+	    anonymize: false,
+        };
+	assert!(mmds_config.ipv6_addr_pool().is_ok());
+
+        let wrong_mmds_config = MmdsConfig {
+	    ipv4_address_pool: Vec::new(),
+	    ipv6_address_pool: vec!["not_an_ipv6".to_string()],
+	    mdns_hostname: None,
+	    endpoint: None,
+	    dns_enabled: false,
+	    anonymize: false,
+        };
+	assert!(wrong_mmds_config.ipv6_addr_pool().is_err());
+    }
+
+    #[test]
+    fn test_matches_ipv4_and_ipv6() {
+        use std::net::{Ipv4Addr, Ipv6Addr};
+
+        let mmds_config = MmdsConfig {
+	    ipv4_address_pool: vec!["1.1.1.1".to_string()],
+	    ipv6_address_pool: vec!["fe80::1".to_string()],
+	    mdns_hostname: None,
+	    endpoint: None,
+	    dns_enabled: false,
+	    anonymize: false,
+        };
+
+	let resolved = mmds_config.resolve_pools().unwrap();
+	assert!(resolved.matches_ipv4(Ipv4Addr::new(1, 1, 1, 1)));
+	assert!(!resolved.matches_ipv4(Ipv4Addr::new(2, 2, 2, 2)));
+	assert!(resolved.matches_ipv6("fe80::1".parse::<Ipv6Addr>().unwrap()));
+	assert!(!resolved.matches_ipv6("fe80::2".parse::<Ipv6Addr>().unwrap()));
+    }
+
+    #[test]
+    fn test_resolve_pools_surfaces_fqdn_resolution_failure() {
+        // An unresolvable hostname pool entry must make resolve_pools() fail
+        // loudly, rather than being swallowed into a "no match" the way the
+        // old per-call matches_ipv4/matches_ipv6 used to (via unwrap_or(false)).
+        let mmds_config = MmdsConfig {
+	    ipv4_address_pool: vec!["no-such-host.invalid".to_string()],
+	    ipv6_address_pool: Vec::new(),
+	    mdns_hostname: None,
+	    endpoint: None,
+	    dns_enabled: false,
+	    anonymize: false,
+        };
+
+	assert!(mmds_config.resolve_pools().is_err());
+    }
+
+    #[test]
+    fn test_ipv4_addr_pool_fqdn_resolution_failure() {
+        use std::io;
+        use vmm_config::mmds::resolve_ipv4_hostname_with;
+
+        // A resolver that returns no addresses at all, e.g. NXDOMAIN.
+        let result = resolve_ipv4_hostname_with("no-such-host.invalid", |_| Ok(Vec::new()));
+        assert!(result.is_err());
+
+        // A resolver that fails outright.
+        let result = resolve_ipv4_hostname_with("no-such-host.invalid", |_| {
+	    Err(io::Error::new(io::ErrorKind::Other, "lookup failed"))
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_ipv4_addr_pool_fqdn_resolution_success() {
+        use std::net::{Ipv4Addr, SocketAddr};
+        use vmm_config::mmds::resolve_ipv4_hostname_with;
+
+        let result = resolve_ipv4_hostname_with("metadata.internal", |_| {
+	    Ok(vec![SocketAddr::from(([10, 0, 0, 1], 0))])
+        });
+	assert_eq!(result.unwrap(), vec![Ipv4Addr::new(10, 0, 0, 1)]);
+    }
+
+    #[test]
+    fn test_endpoint() {
+        let mmds_config = MmdsConfig {
+	    ipv4_address_pool: Vec::new(),
+	    ipv6_address_pool: Vec::new(),
+	    mdns_hostname: None,
+	    endpoint: None,
+	    dns_enabled: false,
+	    anonymize: false,
+        };
+	assert!(mmds_config.endpoint().unwrap().is_none());
+
+        let mmds_config = MmdsConfig {
+	    ipv4_address_pool: Vec::new(),
+	    ipv6_address_pool: Vec::new(),
+	    mdns_hostname: None,
+	    endpoint: Some("/ip4/169.254.169.254/tcp/80/http".to_string()),
+	    dns_enabled: false,
+	    anonymize: false,
+        };
+	assert!(mmds_config.endpoint().unwrap().is_some());
+
+        let wrong_mmds_config = MmdsConfig {
+	    ipv4_address_pool: Vec::new(),
+	    ipv6_address_pool: Vec::new(),
+	    mdns_hostname: None,
+	    endpoint: Some("/ip4/not_an_address/tcp/80/http".to_string()),
+	    dns_enabled: false,
+	    anonymize: false,
+        };
+	assert!(wrong_mmds_config.endpoint().is_err());
+    }
 }
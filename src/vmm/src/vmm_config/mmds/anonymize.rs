@@ -0,0 +1,60 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Redaction helper for `MmdsConfig::anonymize`, so MMDS puts/patches can be
+//! logged without leaking secrets embedded in the metadata document. Mirrors
+//! the anonymized-logging approach used for sensitive session data: the
+//! document's shape (object/array nesting and key names) is preserved, only
+//! string leaf values are replaced.
+
+use serde_json::Value;
+
+/// Placeholder substituted for every string leaf value.
+const REDACTED_PLACEHOLDER: &str = "<redacted>";
+
+/// Returns a copy of `value` with every string leaf replaced by a stable
+/// placeholder, preserving object keys, array order and non-string leaves
+/// (numbers, booleans, null) as-is.
+pub fn anonymize(value: &Value) -> Value {
+    match value {
+        Value::String(_) => Value::String(REDACTED_PLACEHOLDER.to_string()),
+        Value::Array(values) => Value::Array(values.iter().map(anonymize).collect()),
+        Value::Object(map) => {
+            Value::Object(map.iter().map(|(k, v)| (k.clone(), anonymize(v))).collect())
+        }
+        other => other.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_anonymize_preserves_structure() {
+        let doc = json!({
+            "instance-id": "i-1234567890abcdef0",
+            "public-keys": ["ssh-rsa AAAA...", "ssh-rsa BBBB..."],
+            "network": {"interfaces": 2, "enabled": true},
+            "nothing": null,
+        });
+
+        let anonymized = anonymize(&doc);
+
+        assert_eq!(anonymized["instance-id"], json!(REDACTED_PLACEHOLDER));
+        assert_eq!(
+            anonymized["public-keys"],
+            json!([REDACTED_PLACEHOLDER, REDACTED_PLACEHOLDER])
+        );
+        assert_eq!(anonymized["network"]["interfaces"], json!(2));
+        assert_eq!(anonymized["network"]["enabled"], json!(true));
+        assert_eq!(anonymized["nothing"], json!(null));
+    }
+
+    #[test]
+    fn test_anonymize_scalar() {
+        assert_eq!(anonymize(&json!("secret")), json!(REDACTED_PLACEHOLDER));
+        assert_eq!(anonymize(&json!(42)), json!(42));
+    }
+}
@@ -0,0 +1,252 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! DNS TXT front-end for MMDS, gated behind `MmdsConfig::dns_enabled`. Lets
+//! guests resolve a dotted name (e.g. `region.placement`) to the matching
+//! value in the stored metadata JSON tree, for tooling that only speaks DNS.
+
+use serde_json::Value;
+
+/// Well-known DNS port the front-end listens on.
+pub const DNS_PORT: u16 = 53;
+
+/// DNS query type `A`.
+const QTYPE_A: u16 = 1;
+/// DNS query type `TXT`.
+const QTYPE_TXT: u16 = 16;
+
+/// DNS response codes used by the front-end.
+const RCODE_NO_ERROR: u16 = 0;
+/// `FORMERR`: the query could not be understood (e.g. unsupported qtype).
+const RCODE_FORM_ERR: u16 = 1;
+/// `NXDOMAIN`: the requested name does not map to any metadata.
+const RCODE_NAME_ERROR: u16 = 3;
+/// Standard response flag: response bit set, recursion not available.
+const RESPONSE_FLAGS: u16 = 0x8000;
+
+/// A question parsed out of an inbound DNS query.
+struct Question {
+    /// Dotted name, in wire order (as received on the wire).
+    name: String,
+    qtype: u16,
+    /// Offset of the first byte past this question's qtype/qclass, i.e.
+    /// where the answer section would start.
+    end: usize,
+}
+
+/// Errors that can occur while answering a DNS TXT query.
+#[derive(Debug)]
+pub enum Error {
+    /// The query packet was malformed and could not be parsed.
+    MalformedQuery,
+}
+
+/// Given a raw DNS query packet and the root of the stored metadata JSON
+/// tree, builds the matching DNS response: a `TXT` answer on a hit, an
+/// `NXDOMAIN` when the path isn't present, or a `FORMERR` for any qtype
+/// other than `TXT`/`A`.
+pub fn handle_query(query: &[u8], metadata: &Value) -> Result<Vec<u8>, Error> {
+    let question = parse_question(query).ok_or(Error::MalformedQuery)?;
+
+    if question.qtype != QTYPE_TXT && question.qtype != QTYPE_A {
+        return Ok(build_response(query, question.end, RCODE_FORM_ERR, None));
+    }
+
+    let path = dotted_name_to_path(&question.name);
+    match lookup(metadata, &path) {
+        Some(value) => Ok(build_response(query, question.end, RCODE_NO_ERROR, Some(value))),
+        None => Ok(build_response(query, question.end, RCODE_NAME_ERROR, None)),
+    }
+}
+
+/// Reverses a dotted query name's labels into a JSON path, e.g.
+/// `region.placement` becomes `["placement", "region"]`.
+fn dotted_name_to_path(name: &str) -> Vec<&str> {
+    let mut labels: Vec<&str> = name.split('.').collect();
+    labels.reverse();
+    labels
+}
+
+/// Walks `root` following `path`, returning the leaf value if it is a
+/// string or a number, and `None` if the path doesn't resolve to one.
+fn lookup(root: &Value, path: &[&str]) -> Option<String> {
+    let mut node = root;
+    for segment in path {
+        node = node.get(segment)?;
+    }
+
+    match node {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// Parses the single question carried by a DNS query packet.
+fn parse_question(buf: &[u8]) -> Option<Question> {
+    if buf.len() < 12 {
+        return None;
+    }
+
+    let mut labels = Vec::new();
+    let mut pos = 12;
+    loop {
+        let len = *buf.get(pos)? as usize;
+        pos += 1;
+        if len == 0 {
+            break;
+        }
+        let label = buf.get(pos..pos + len)?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        pos += len;
+    }
+
+    // Require both qtype and qclass to be present, even though only qtype is
+    // inspected below: a query truncated right after qtype would otherwise
+    // pass parsing and later panic when the caller slices out the question.
+    let qtype = u16::from(*buf.get(pos)?) << 8 | u16::from(*buf.get(pos + 1)?);
+    buf.get(pos + 2)?;
+    buf.get(pos + 3)?;
+    let end = pos + 4;
+
+    Some(Question {
+        name: labels.join("."),
+        qtype,
+        end,
+    })
+}
+
+/// Builds a DNS response for `query`, with the given `rcode` and an
+/// optional TXT `value` to answer with. `question_end` is the offset, in
+/// `query`, right after the question's qtype/qclass, as returned by
+/// `parse_question`.
+fn build_response(query: &[u8], question_end: usize, rcode: u16, value: Option<String>) -> Vec<u8> {
+    let mut resp = Vec::with_capacity(query.len() + 16);
+
+    let ancount: u16 = if value.is_some() { 1 } else { 0 };
+
+    resp.extend_from_slice(&query[0..2]);
+    resp.extend_from_slice(&(RESPONSE_FLAGS | rcode).to_be_bytes());
+    resp.extend_from_slice(&1u16.to_be_bytes()); // qdcount
+    resp.extend_from_slice(&ancount.to_be_bytes());
+    resp.extend_from_slice(&0u16.to_be_bytes()); // nscount
+    resp.extend_from_slice(&0u16.to_be_bytes()); // arcount
+
+    resp.extend_from_slice(&query[12..question_end]);
+
+    if let Some(value) = value {
+        // Name-compression pointer to the question, back at offset 12.
+        resp.extend_from_slice(&0xC00Cu16.to_be_bytes());
+        resp.extend_from_slice(&QTYPE_TXT.to_be_bytes());
+        resp.extend_from_slice(&1u16.to_be_bytes()); // class IN
+        resp.extend_from_slice(&120u32.to_be_bytes()); // ttl
+
+        let bytes = value.as_bytes();
+        let char_string_len = bytes.len().min(255) as u8;
+        resp.extend_from_slice(&(1u16 + u16::from(char_string_len)).to_be_bytes()); // rdlength
+        resp.push(char_string_len);
+        resp.extend_from_slice(&bytes[..char_string_len as usize]);
+    }
+
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn encode_query(name: &str, qtype: u16) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&0xABCDu16.to_be_bytes()); // id
+        buf.extend_from_slice(&0u16.to_be_bytes()); // flags
+        buf.extend_from_slice(&1u16.to_be_bytes()); // qdcount
+        buf.extend_from_slice(&0u16.to_be_bytes()); // ancount
+        buf.extend_from_slice(&0u16.to_be_bytes()); // nscount
+        buf.extend_from_slice(&0u16.to_be_bytes()); // arcount
+
+        for label in name.split('.') {
+            buf.push(label.len() as u8);
+            buf.extend_from_slice(label.as_bytes());
+        }
+        buf.push(0);
+
+        buf.extend_from_slice(&qtype.to_be_bytes());
+        buf.extend_from_slice(&1u16.to_be_bytes()); // class IN
+
+        buf
+    }
+
+    #[test]
+    fn test_dotted_name_to_path() {
+        assert_eq!(dotted_name_to_path("region.placement"), vec!["placement", "region"]);
+    }
+
+    #[test]
+    fn test_handle_query_txt_hit() {
+        let metadata = json!({"placement": {"region": "us-east-1"}});
+        let query = encode_query("region.placement", QTYPE_TXT);
+        let response = handle_query(&query, &metadata).unwrap();
+
+        assert_eq!(&response[2..4], &(RESPONSE_FLAGS | RCODE_NO_ERROR).to_be_bytes());
+        assert_eq!(&response[6..8], &1u16.to_be_bytes()); // ancount
+
+        let answer = &response[parse_question(&query).unwrap().end..];
+        assert_eq!(&answer[2..4], &QTYPE_TXT.to_be_bytes());
+        let rdlength = u16::from(answer[10]) << 8 | u16::from(answer[11]);
+        assert_eq!(rdlength as usize, 1 + "us-east-1".len());
+        assert_eq!(answer[12], "us-east-1".len() as u8);
+        assert_eq!(&answer[13..13 + "us-east-1".len()], b"us-east-1");
+    }
+
+    #[test]
+    fn test_handle_query_nxdomain() {
+        let metadata = json!({"placement": {"region": "us-east-1"}});
+        let query = encode_query("missing.key", QTYPE_TXT);
+        let response = handle_query(&query, &metadata).unwrap();
+
+        assert_eq!(&response[2..4], &(RESPONSE_FLAGS | RCODE_NAME_ERROR).to_be_bytes());
+        assert_eq!(&response[6..8], &0u16.to_be_bytes()); // ancount
+    }
+
+    #[test]
+    fn test_handle_query_a_hit() {
+        let metadata = json!({"placement": {"region": "us-east-1"}});
+        let query = encode_query("region.placement", QTYPE_A);
+        let response = handle_query(&query, &metadata).unwrap();
+
+        assert_eq!(&response[2..4], &(RESPONSE_FLAGS | RCODE_NO_ERROR).to_be_bytes());
+        assert_eq!(&response[6..8], &1u16.to_be_bytes()); // ancount
+    }
+
+    #[test]
+    fn test_handle_query_form_err_for_unsupported_qtype() {
+        // MX (15) is neither of the two qtypes this front-end understands.
+        const QTYPE_MX: u16 = 15;
+        let metadata = json!({"placement": {"region": "us-east-1"}});
+        let query = encode_query("region.placement", QTYPE_MX);
+        let response = handle_query(&query, &metadata).unwrap();
+
+        assert_eq!(&response[2..4], &(RESPONSE_FLAGS | RCODE_FORM_ERR).to_be_bytes());
+        assert_eq!(&response[6..8], &0u16.to_be_bytes()); // ancount
+    }
+
+    #[test]
+    fn test_handle_query_malformed() {
+        let metadata = json!({});
+        assert!(handle_query(&[0u8; 4], &metadata).is_err());
+    }
+
+    #[test]
+    fn test_handle_query_truncated_after_qtype_does_not_panic() {
+        // A query whose question section ends 2 bytes short of a full
+        // qtype/qclass pair (qclass missing) must be rejected by
+        // `parse_question` instead of reaching `build_response`, which
+        // would otherwise slice out of bounds.
+        let mut query = encode_query("region.placement", QTYPE_TXT);
+        query.truncate(query.len() - 2);
+
+        let metadata = json!({"placement": {"region": "us-east-1"}});
+        assert!(handle_query(&query, &metadata).is_err());
+    }
+}
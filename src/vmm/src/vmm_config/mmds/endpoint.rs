@@ -0,0 +1,170 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Parser for multiaddr-style MMDS endpoint strings, e.g.
+//! `/ip4/169.254.169.254/tcp/80/http`, letting operators control which
+//! address, port and application protocol the guest uses to reach MMDS.
+
+use std::fmt::{Display, Formatter};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+/// Address family component of an MMDS endpoint.
+#[derive(Debug, PartialEq)]
+pub enum AddressFamily {
+    /// `/ip4/<addr>`.
+    Ip4,
+    /// `/ip6/<addr>`.
+    Ip6,
+}
+
+/// Transport component of an MMDS endpoint.
+#[derive(Debug, PartialEq)]
+pub enum Transport {
+    /// `/tcp/<port>`.
+    Tcp,
+    /// `/udp/<port>`.
+    Udp,
+}
+
+/// Application protocol component of an MMDS endpoint.
+#[derive(Debug, PartialEq)]
+pub enum AppProtocol {
+    /// `/http`.
+    Http,
+    /// `/https`.
+    Https,
+}
+
+/// A fully parsed multiaddr-style MMDS endpoint.
+#[derive(Debug, PartialEq)]
+pub struct MmdsEndpoint {
+    /// Address family of `address`.
+    pub address_family: AddressFamily,
+    /// Address the MMDS service should bind/listen on.
+    pub address: IpAddr,
+    /// Transport protocol the guest connects with.
+    pub transport: Transport,
+    /// Port the MMDS service should bind/listen on.
+    pub port: u16,
+    /// Application-level protocol spoken on top of `transport`.
+    pub app_protocol: AppProtocol,
+}
+
+/// Errors encountered while parsing a multiaddr-style endpoint string.
+#[derive(Debug)]
+pub enum Error {
+    /// A `/`-delimited segment did not match any known protocol code.
+    UnknownProtocol(String),
+    /// A protocol code that requires a value (e.g. `ip4`, `tcp`) was the
+    /// last segment in the string.
+    MissingValue(&'static str),
+    /// The endpoint was missing a required component (e.g. no transport).
+    MissingComponent(&'static str),
+    /// The address value could not be parsed for the given address family.
+    InvalidAddress(String),
+    /// The port value was not a valid `u16`.
+    InvalidPort(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Error::UnknownProtocol(code) => write!(f, "Unknown MMDS endpoint protocol `{}`.", code),
+            Error::MissingValue(code) => {
+                write!(f, "Protocol `{}` in the MMDS endpoint is missing its value.", code)
+            }
+            Error::MissingComponent(component) => {
+                write!(f, "The MMDS endpoint is missing a `{}` component.", component)
+            }
+            Error::InvalidAddress(addr) => write!(f, "Invalid MMDS endpoint address `{}`.", addr),
+            Error::InvalidPort(port) => write!(f, "Invalid MMDS endpoint port `{}`.", port),
+        }
+    }
+}
+
+/// Parses a multiaddr-style endpoint string such as
+/// `/ip4/169.254.169.254/tcp/80/http` into its components.
+pub fn parse_endpoint(endpoint: &str) -> Result<MmdsEndpoint, Error> {
+    let mut tokens = endpoint.split('/').filter(|s| !s.is_empty());
+
+    let mut address_family = None;
+    let mut address = None;
+    let mut transport = None;
+    let mut port = None;
+    let mut app_protocol = None;
+
+    while let Some(code) = tokens.next() {
+        match code {
+            "ip4" => {
+                let value = tokens.next().ok_or(Error::MissingValue("ip4"))?;
+                address_family = Some(AddressFamily::Ip4);
+                address = Some(IpAddr::V4(
+                    Ipv4Addr::from_str(value).map_err(|_| Error::InvalidAddress(value.to_string()))?,
+                ));
+            }
+            "ip6" => {
+                let value = tokens.next().ok_or(Error::MissingValue("ip6"))?;
+                address_family = Some(AddressFamily::Ip6);
+                address = Some(IpAddr::V6(
+                    Ipv6Addr::from_str(value).map_err(|_| Error::InvalidAddress(value.to_string()))?,
+                ));
+            }
+            "tcp" => {
+                let value = tokens.next().ok_or(Error::MissingValue("tcp"))?;
+                transport = Some(Transport::Tcp);
+                port = Some(u16::from_str(value).map_err(|_| Error::InvalidPort(value.to_string()))?);
+            }
+            "udp" => {
+                let value = tokens.next().ok_or(Error::MissingValue("udp"))?;
+                transport = Some(Transport::Udp);
+                port = Some(u16::from_str(value).map_err(|_| Error::InvalidPort(value.to_string()))?);
+            }
+            "http" => app_protocol = Some(AppProtocol::Http),
+            "https" => app_protocol = Some(AppProtocol::Https),
+            other => return Err(Error::UnknownProtocol(other.to_string())),
+        }
+    }
+
+    Ok(MmdsEndpoint {
+        address_family: address_family.ok_or(Error::MissingComponent("address family"))?,
+        address: address.ok_or(Error::MissingComponent("address"))?,
+        transport: transport.ok_or(Error::MissingComponent("transport"))?,
+        port: port.ok_or(Error::MissingComponent("port"))?,
+        app_protocol: app_protocol.ok_or(Error::MissingComponent("application protocol"))?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_endpoint() {
+        let endpoint = parse_endpoint("/ip4/169.254.169.254/tcp/80/http").unwrap();
+        assert_eq!(endpoint.address_family, AddressFamily::Ip4);
+        assert_eq!(endpoint.address, IpAddr::V4(Ipv4Addr::new(169, 254, 169, 254)));
+        assert_eq!(endpoint.transport, Transport::Tcp);
+        assert_eq!(endpoint.port, 80);
+        assert_eq!(endpoint.app_protocol, AppProtocol::Http);
+    }
+
+    #[test]
+    fn test_parse_endpoint_ip6_udp_https() {
+        let endpoint = parse_endpoint("/ip6/fe80::1/udp/8080/https").unwrap();
+        assert_eq!(endpoint.address_family, AddressFamily::Ip6);
+        assert_eq!(endpoint.transport, Transport::Udp);
+        assert_eq!(endpoint.port, 8080);
+        assert_eq!(endpoint.app_protocol, AppProtocol::Https);
+    }
+
+    #[test]
+    fn test_parse_endpoint_errors() {
+        assert!(parse_endpoint("/ip4/not_an_address/tcp/80/http").is_err());
+        assert!(parse_endpoint("/ip4/169.254.169.254/tcp/not_a_port/http").is_err());
+        assert!(parse_endpoint("/ip4/169.254.169.254/tcp/80/carrier-pigeon").is_err());
+        assert!(parse_endpoint("/ip4/169.254.169.254/tcp/80").is_err());
+        assert!(parse_endpoint("/ip4/169.254.169.254").is_err());
+        assert!(parse_endpoint("/ip4").is_err());
+    }
+}
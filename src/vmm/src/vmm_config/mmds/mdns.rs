@@ -0,0 +1,373 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Minimal mDNS responder used so guests can resolve a configured MMDS
+//! hostname (e.g. `metadata.local`) to an address from the MMDS pool,
+//! instead of relying on a hardcoded link-local address.
+
+use std::io;
+use std::mem;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, UdpSocket};
+use std::os::unix::io::FromRawFd;
+
+use libc;
+
+/// Multicast group mDNS queries are sent to.
+pub const MDNS_MULTICAST_ADDR: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+/// Well-known mDNS UDP port.
+pub const MDNS_PORT: u16 = 5353;
+
+/// DNS query type A (host address).
+const QTYPE_A: u16 = 1;
+/// DNS query type AAAA (IPv6 host address).
+const QTYPE_AAAA: u16 = 28;
+/// Top bit of qclass in the question section signals a unicast-preferred
+/// response, per the mDNS spec.
+const QCLASS_UNICAST_RESPONSE_BIT: u16 = 0x8000;
+/// DNS class IN, with the mDNS cache-flush bit set, used on the answer.
+const QCLASS_IN_CACHE_FLUSH: u16 = 0x8001;
+/// Flags set on a successful, authoritative mDNS response.
+const RESPONSE_FLAGS: u16 = 0x8400;
+/// Name compression pointer to offset 12, i.e. right after the header,
+/// which is where the question's name starts in our responses.
+const NAME_POINTER: u16 = 0xC00C;
+/// TTL, in seconds, advertised on the answer record.
+const ANSWER_TTL: u32 = 120;
+
+/// Errors that can occur when setting up or running the mDNS responder.
+#[derive(Debug)]
+pub enum Error {
+    /// Failed to create or configure the responder's UDP socket.
+    Socket(io::Error),
+    /// Failed to join the mDNS multicast group.
+    JoinMulticast(io::Error),
+}
+
+/// A question parsed out of an inbound mDNS query.
+struct Question {
+    /// The queried name, with labels joined by dots.
+    name: String,
+    qtype: u16,
+    /// Whether the querier asked for a unicast response.
+    unicast_response: bool,
+}
+
+/// Responds to mDNS `A`/`AAAA` queries for a single configured hostname with
+/// an address taken from the MMDS pools.
+pub struct MdnsResponder {
+    socket: UdpSocket,
+    hostname: String,
+    ipv4_address: Option<Ipv4Addr>,
+    ipv6_address: Option<Ipv6Addr>,
+}
+
+impl MdnsResponder {
+    /// Creates a responder bound to the mDNS multicast group, ready to
+    /// answer queries for `hostname` with `ipv4_address`/`ipv6_address`,
+    /// whichever is set (an `A` query is ignored if `ipv4_address` is
+    /// `None`, likewise for `AAAA`/`ipv6_address`).
+    ///
+    /// Only the IPv4 multicast group is joined: this responder only ever
+    /// opens an `AF_INET` socket, so an `AAAA` answer only reaches queriers
+    /// that are themselves on that IPv4 multicast group, or that queried us
+    /// directly (unicast).
+    pub fn new(
+        hostname: String,
+        ipv4_address: Option<Ipv4Addr>,
+        ipv6_address: Option<Ipv6Addr>,
+    ) -> Result<MdnsResponder, Error> {
+        let socket = new_reusable_udp_socket(MDNS_PORT).map_err(Error::Socket)?;
+        socket
+            .join_multicast_v4(&MDNS_MULTICAST_ADDR, &Ipv4Addr::UNSPECIFIED)
+            .map_err(Error::JoinMulticast)?;
+
+        Ok(MdnsResponder {
+            socket,
+            hostname,
+            ipv4_address,
+            ipv6_address,
+        })
+    }
+
+    /// Blocks until an inbound mDNS query arrives, then reads and answers it.
+    /// Queries for names other than the configured hostname, or for a
+    /// qtype whose address isn't configured, are ignored.
+    pub fn respond_once(&self) -> Result<(), Error> {
+        let mut buf = [0u8; 512];
+        let (len, from) = self.socket.recv_from(&mut buf).map_err(Error::Socket)?;
+
+        if let Some(question) = parse_question(&buf[..len]) {
+            if question.name != self.hostname {
+                return Ok(());
+            }
+
+            let response = match (question.qtype, self.ipv4_address, self.ipv6_address) {
+                (QTYPE_A, Some(address), _) => build_a_response(&buf[..len], address),
+                (QTYPE_AAAA, _, Some(address)) => build_aaaa_response(&buf[..len], address),
+                _ => return Ok(()),
+            };
+
+            let dest = if question.unicast_response {
+                from
+            } else {
+                (MDNS_MULTICAST_ADDR, MDNS_PORT).into()
+            };
+            self.socket.send_to(&response, dest).map_err(Error::Socket)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Creates a UDP socket with `SO_REUSEADDR` and `SO_REUSEPORT` set before
+/// binding, bound to `0.0.0.0:port`. Both options are required so multiple
+/// mDNS responders on the host can share the well-known port; reused by
+/// `service::spawn_dns_frontend` for the same reason on its own port.
+pub(crate) fn new_reusable_udp_socket(port: u16) -> io::Result<UdpSocket> {
+    // Safe because we only pass well-formed arguments for an IPv4/UDP socket
+    // and check the return value for errors below.
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let enable: libc::c_int = 1;
+    for opt in &[libc::SO_REUSEADDR, libc::SO_REUSEPORT] {
+        // Safe because `fd` was just created above and `enable` outlives the call.
+        let ret = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                *opt,
+                &enable as *const libc::c_int as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            let err = io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            return Err(err);
+        }
+    }
+
+    let addr = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port);
+    let sockaddr = libc::sockaddr_in {
+        sin_family: libc::AF_INET as libc::sa_family_t,
+        sin_port: addr.port().to_be(),
+        sin_addr: libc::in_addr {
+            s_addr: u32::from(*addr.ip()).to_be(),
+        },
+        sin_zero: [0; 8],
+    };
+    // Safe because `fd` is a valid, freshly created socket and `sockaddr` is
+    // a correctly sized and populated `sockaddr_in`.
+    let ret = unsafe {
+        libc::bind(
+            fd,
+            &sockaddr as *const libc::sockaddr_in as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        let err = io::Error::last_os_error();
+        unsafe { libc::close(fd) };
+        return Err(err);
+    }
+
+    // Safe because `fd` is a valid, open socket that we no longer touch
+    // through the raw libc API past this point.
+    Ok(unsafe { UdpSocket::from_raw_fd(fd) })
+}
+
+/// Parses the single question carried by an mDNS query packet.
+/// Returns `None` if the packet is malformed.
+fn parse_question(buf: &[u8]) -> Option<Question> {
+    if buf.len() < 12 {
+        return None;
+    }
+    let qdcount = u16::from(buf[4]) << 8 | u16::from(buf[5]);
+    if qdcount == 0 {
+        return None;
+    }
+
+    let mut labels = Vec::new();
+    let mut pos = 12;
+    loop {
+        let len = *buf.get(pos)? as usize;
+        pos += 1;
+        if len == 0 {
+            break;
+        }
+        let label = buf.get(pos..pos + len)?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        pos += len;
+    }
+
+    let qtype = u16::from(*buf.get(pos)?) << 8 | u16::from(*buf.get(pos + 1)?);
+    let qclass_raw = u16::from(*buf.get(pos + 2)?) << 8 | u16::from(*buf.get(pos + 3)?);
+
+    Some(Question {
+        name: labels.join("."),
+        qtype,
+        unicast_response: qclass_raw & QCLASS_UNICAST_RESPONSE_BIT != 0,
+    })
+}
+
+/// Builds an mDNS response answering the query in `query` with a single
+/// `A` record pointing at `address`.
+fn build_a_response(query: &[u8], address: Ipv4Addr) -> Vec<u8> {
+    let mut resp = Vec::with_capacity(query.len() + 16);
+
+    // Header: id copied from the query, flags/counts per mDNS answer rules.
+    resp.extend_from_slice(&query[0..2]);
+    resp.extend_from_slice(&RESPONSE_FLAGS.to_be_bytes());
+    resp.extend_from_slice(&1u16.to_be_bytes()); // qdcount
+    resp.extend_from_slice(&1u16.to_be_bytes()); // ancount
+    resp.extend_from_slice(&0u16.to_be_bytes()); // nscount
+    resp.extend_from_slice(&0u16.to_be_bytes()); // arcount
+
+    // Echo the question section verbatim.
+    let question_end = find_question_end(query);
+    resp.extend_from_slice(&query[12..question_end]);
+
+    // Answer: name-compressed pointer to the question, then type/class/ttl/rdata.
+    resp.extend_from_slice(&NAME_POINTER.to_be_bytes());
+    resp.extend_from_slice(&QTYPE_A.to_be_bytes());
+    resp.extend_from_slice(&QCLASS_IN_CACHE_FLUSH.to_be_bytes());
+    resp.extend_from_slice(&ANSWER_TTL.to_be_bytes());
+    resp.extend_from_slice(&4u16.to_be_bytes()); // rdlength
+    resp.extend_from_slice(&address.octets());
+
+    resp
+}
+
+/// Builds an mDNS response answering the query in `query` with a single
+/// `AAAA` record pointing at `address`.
+fn build_aaaa_response(query: &[u8], address: Ipv6Addr) -> Vec<u8> {
+    let mut resp = Vec::with_capacity(query.len() + 28);
+
+    // Header: id copied from the query, flags/counts per mDNS answer rules.
+    resp.extend_from_slice(&query[0..2]);
+    resp.extend_from_slice(&RESPONSE_FLAGS.to_be_bytes());
+    resp.extend_from_slice(&1u16.to_be_bytes()); // qdcount
+    resp.extend_from_slice(&1u16.to_be_bytes()); // ancount
+    resp.extend_from_slice(&0u16.to_be_bytes()); // nscount
+    resp.extend_from_slice(&0u16.to_be_bytes()); // arcount
+
+    // Echo the question section verbatim.
+    let question_end = find_question_end(query);
+    resp.extend_from_slice(&query[12..question_end]);
+
+    // Answer: name-compressed pointer to the question, then type/class/ttl/rdata.
+    resp.extend_from_slice(&NAME_POINTER.to_be_bytes());
+    resp.extend_from_slice(&QTYPE_AAAA.to_be_bytes());
+    resp.extend_from_slice(&QCLASS_IN_CACHE_FLUSH.to_be_bytes());
+    resp.extend_from_slice(&ANSWER_TTL.to_be_bytes());
+    resp.extend_from_slice(&16u16.to_be_bytes()); // rdlength
+    resp.extend_from_slice(&address.octets());
+
+    resp
+}
+
+/// Returns the offset right after the qtype/qclass of the first question.
+fn find_question_end(buf: &[u8]) -> usize {
+    let mut pos = 12;
+    loop {
+        let len = buf[pos] as usize;
+        pos += 1;
+        if len == 0 {
+            break;
+        }
+        pos += len;
+    }
+    // Skip qtype and qclass.
+    pos + 4
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_query(name: &str, qtype: u16, unicast_response: bool) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&0x1234u16.to_be_bytes()); // id
+        buf.extend_from_slice(&0u16.to_be_bytes()); // flags
+        buf.extend_from_slice(&1u16.to_be_bytes()); // qdcount
+        buf.extend_from_slice(&0u16.to_be_bytes()); // ancount
+        buf.extend_from_slice(&0u16.to_be_bytes()); // nscount
+        buf.extend_from_slice(&0u16.to_be_bytes()); // arcount
+
+        for label in name.split('.') {
+            buf.push(label.len() as u8);
+            buf.extend_from_slice(label.as_bytes());
+        }
+        buf.push(0);
+
+        buf.extend_from_slice(&qtype.to_be_bytes());
+        let mut qclass = 1u16;
+        if unicast_response {
+            qclass |= QCLASS_UNICAST_RESPONSE_BIT;
+        }
+        buf.extend_from_slice(&qclass.to_be_bytes());
+
+        buf
+    }
+
+    #[test]
+    fn test_parse_question() {
+        let query = encode_query("metadata.local", QTYPE_A, true);
+        let question = parse_question(&query).unwrap();
+        assert_eq!(question.name, "metadata.local");
+        assert_eq!(question.qtype, QTYPE_A);
+        assert!(question.unicast_response);
+
+        let query = encode_query("metadata.local", QTYPE_AAAA, false);
+        let question = parse_question(&query).unwrap();
+        assert_eq!(question.qtype, QTYPE_AAAA);
+        assert!(!question.unicast_response);
+
+        assert!(parse_question(&[0u8; 4]).is_none());
+    }
+
+    #[test]
+    fn test_build_a_response() {
+        let query = encode_query("metadata.local", QTYPE_A, false);
+        let address = Ipv4Addr::new(169, 254, 169, 254);
+        let response = build_a_response(&query, address);
+
+        assert_eq!(&response[0..2], &query[0..2]);
+        assert_eq!(&response[2..4], &RESPONSE_FLAGS.to_be_bytes());
+        assert_eq!(&response[4..6], &1u16.to_be_bytes());
+        assert_eq!(&response[6..8], &1u16.to_be_bytes());
+
+        let answer_start = find_question_end(&query);
+        let answer = &response[answer_start..];
+        assert_eq!(&answer[0..2], &NAME_POINTER.to_be_bytes());
+        assert_eq!(&answer[2..4], &QTYPE_A.to_be_bytes());
+        assert_eq!(&answer[4..6], &QCLASS_IN_CACHE_FLUSH.to_be_bytes());
+        assert_eq!(&answer[6..10], &ANSWER_TTL.to_be_bytes());
+        assert_eq!(&answer[10..12], &4u16.to_be_bytes());
+        assert_eq!(&answer[12..16], &address.octets());
+    }
+
+    #[test]
+    fn test_build_aaaa_response() {
+        let query = encode_query("metadata.local", QTYPE_AAAA, false);
+        let address = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+        let response = build_aaaa_response(&query, address);
+
+        assert_eq!(&response[0..2], &query[0..2]);
+        assert_eq!(&response[2..4], &RESPONSE_FLAGS.to_be_bytes());
+        assert_eq!(&response[4..6], &1u16.to_be_bytes());
+        assert_eq!(&response[6..8], &1u16.to_be_bytes());
+
+        let answer_start = find_question_end(&query);
+        let answer = &response[answer_start..];
+        assert_eq!(&answer[0..2], &NAME_POINTER.to_be_bytes());
+        assert_eq!(&answer[2..4], &QTYPE_AAAA.to_be_bytes());
+        assert_eq!(&answer[4..6], &QCLASS_IN_CACHE_FLUSH.to_be_bytes());
+        assert_eq!(&answer[6..10], &ANSWER_TTL.to_be_bytes());
+        assert_eq!(&answer[10..12], &16u16.to_be_bytes());
+        assert_eq!(&answer[12..28], &address.octets());
+    }
+}
@@ -0,0 +1,160 @@
+// Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Spawns the optional MMDS auxiliary listeners (mDNS responder, DNS TXT
+//! front-end) described by an `MmdsConfig`, alongside the main MMDS HTTP
+//! service.
+
+use std::net::{TcpListener, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use serde_json::Value;
+
+use super::dns;
+use super::endpoint::{MmdsEndpoint, Transport};
+use super::mdns::{self, MdnsResponder};
+use super::MmdsConfig;
+
+/// Set once an mDNS responder thread is running, so a second `PUT
+/// /mmds/config` (a realistic retry/reconfigure) doesn't leak another one
+/// bound to the same port underneath it.
+static MDNS_RESPONDER_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Set once the DNS TXT front-end thread is running; see
+/// `MDNS_RESPONDER_RUNNING`.
+static DNS_FRONTEND_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Spawns a background thread running the mDNS responder described by
+/// `config`, if `config.mdns_hostname()` is set. The responder answers `A`
+/// queries with the first address in the configured IPv4 pool and `AAAA`
+/// queries with the first address in the configured IPv6 pool. Both are sent
+/// over the responder's IPv4 socket (see `MdnsResponder::new`); no IPv6
+/// multicast group is joined.
+///
+/// A no-op, with a visible warning, if a responder from an earlier config
+/// PUT is already running: reconfiguring the hostname/addresses at runtime
+/// isn't supported yet, so the safe thing is to leave the existing
+/// responder alone rather than spawn a second one racing it for the port.
+pub fn spawn_mdns_responder(config: &MmdsConfig) {
+    let hostname = match config.mdns_hostname() {
+        Some(hostname) => hostname.to_string(),
+        None => return,
+    };
+
+    let ipv4_address = config.ipv4_addr_pool().ok().and_then(|pool| pool.into_iter().next());
+    let ipv6_address = config.ipv6_addr_pool().ok().and_then(|pool| pool.into_iter().next());
+    if ipv4_address.is_none() && ipv6_address.is_none() {
+        return;
+    }
+
+    if MDNS_RESPONDER_RUNNING.swap(true, Ordering::SeqCst) {
+        eprintln!(
+            "An mDNS responder is already running; ignoring the new MMDS config's \
+             mdns_hostname/address pool until the VMM is restarted."
+        );
+        return;
+    }
+
+    thread::spawn(move || {
+        if let Ok(responder) = MdnsResponder::new(hostname, ipv4_address, ipv6_address) {
+            loop {
+                if responder.respond_once().is_err() {
+                    break;
+                }
+            }
+        }
+        MDNS_RESPONDER_RUNNING.store(false, Ordering::SeqCst);
+    });
+}
+
+/// Spawns a background thread running the DNS TXT front-end described by
+/// `config`, if `config.dns_enabled()` is set. Queries are answered against
+/// `metadata`, which callers update independently whenever a PUT/PATCH MMDS
+/// request changes the stored document; passing the same backing store used
+/// to answer those PUT/PATCH requests is what lets the front-end resolve
+/// real keys.
+///
+/// A no-op, with a visible warning, if a front-end from an earlier config PUT
+/// is already running (see `spawn_mdns_responder`). The socket is bound with
+/// `SO_REUSEADDR`/`SO_REUSEPORT` (via `mdns::new_reusable_udp_socket`) purely
+/// as a safety net in case that guard is ever raced; the guard above is what
+/// actually prevents a second front-end from being spawned.
+pub fn spawn_dns_frontend(config: &MmdsConfig, metadata: &'static Mutex<Value>) {
+    if !config.dns_enabled() {
+        return;
+    }
+
+    if DNS_FRONTEND_RUNNING.swap(true, Ordering::SeqCst) {
+        eprintln!(
+            "A DNS TXT front-end is already running; ignoring the new MMDS config's \
+             dns_enabled setting until the VMM is restarted."
+        );
+        return;
+    }
+
+    thread::spawn(move || {
+        let socket = match mdns::new_reusable_udp_socket(dns::DNS_PORT) {
+            Ok(socket) => socket,
+            Err(_) => {
+                DNS_FRONTEND_RUNNING.store(false, Ordering::SeqCst);
+                return;
+            }
+        };
+
+        let mut buf = [0u8; 512];
+        loop {
+            let (len, from) = match socket.recv_from(&mut buf) {
+                Ok(result) => result,
+                Err(_) => break,
+            };
+
+            let document = metadata.lock().unwrap().clone();
+            if let Ok(response) = dns::handle_query(&buf[..len], &document) {
+                let _ = socket.send_to(&response, from);
+            }
+        }
+        DNS_FRONTEND_RUNNING.store(false, Ordering::SeqCst);
+    });
+}
+
+/// Binds the address/port described by `endpoint` instead of whatever
+/// default the MMDS HTTP service would otherwise use.
+///
+/// This tree has no MMDS HTTP service to hand accepted connections off to
+/// (no VMM event loop exists here to own one, same gap noted in
+/// `spawn_mdns_responder`'s and `spawn_dns_frontend`'s callers), so this
+/// only proves out the address/port override by binding it for real;
+/// connections/datagrams are accepted and dropped rather than served.
+pub fn spawn_mmds_listener(endpoint: &MmdsEndpoint) {
+    match endpoint.transport {
+        Transport::Tcp => {
+            let listener = match TcpListener::bind((endpoint.address, endpoint.port)) {
+                Ok(listener) => listener,
+                Err(_) => return,
+            };
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stream.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        Transport::Udp => {
+            let socket = match UdpSocket::bind((endpoint.address, endpoint.port)) {
+                Ok(socket) => socket,
+                Err(_) => return,
+            };
+            thread::spawn(move || {
+                let mut buf = [0u8; 512];
+                loop {
+                    if socket.recv_from(&mut buf).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    }
+}